@@ -16,106 +16,377 @@
 use std::ops::Index;
 use std::sync::Arc as Rc;
 use std::fmt;
-use std::iter::Rev;
-use std::slice::Iter;
+use std::cmp::Ordering;
+use std::ascii::AsciiExt;
+
+use smallvec::SmallVec;
 
 use ::serialize::binary::*;
 use ::error::*;
 
-/// TODO: all Names should be stored in a global "intern" space, and then everything that uses
-///  them should be through references. As a workaround the Strings are all Rc as well as the array
+/// The labels of a name packed into a single buffer plus a side table of
+/// `(offset, length)` spans, in wire order. Most names (e.g. `www.example.com`,
+/// 13 octets across 3 labels) fit entirely in the inline capacity of both
+/// `SmallVec`s, so building a typical `Name` costs a single heap allocation
+/// (the shared `Arc<NameData>`), versus one `Rc` per label plus one for the
+/// label vector previously.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+struct NameData {
+  buf: SmallVec<[u8; 24]>,
+  spans: SmallVec<[(u16, u8); 4]>,
+  fqdn: bool,
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct Name {
-  labels: Rc<Vec<Rc<String>>>
+  data: Rc<NameData>
 }
 
 impl Name {
   pub fn new() -> Self {
-    Name { labels: Rc::new(Vec::new()) }
+    Name { data: Rc::new(NameData { buf: SmallVec::new(), spans: SmallVec::new(), fqdn: true }) }
   }
 
   // inline builder
   pub fn label(mut self, label: &'static str) -> Self {
-    // TODO get_mut() on Arc was unstable when this was written
-    let mut new_labels: Vec<Rc<String>> = (*self.labels).clone();
-    new_labels.push(Rc::new(label.into()));
-    self.labels = Rc::new(new_labels);
+    self.push_label(label);
     self
   }
 
+  /// marks this name as fully-qualified (the default, and the only form a wire
+  /// message or a `parse`d name can take) or relative. Affects `Display` and
+  /// `to_utf8`; being part of the name's data, it also takes part in equality,
+  /// ordering and hashing, so a relative name never equals its absolute twin.
+  pub fn fqdn(mut self, fqdn: bool) -> Self {
+    Rc::make_mut(&mut self.data).fqdn = fqdn;
+    self
+  }
+
+  /// `true` unless explicitly marked relative with `fqdn(false)`
+  pub fn is_fqdn(&self) -> bool {
+    self.data.fqdn
+  }
+
+  /// `true` for the zero-label root name
+  pub fn is_root(&self) -> bool {
+    self.is_empty()
+  }
+
+  /// the number of labels in this name
+  pub fn len(&self) -> usize {
+    self.label_count()
+  }
+
+  /// `true` for the zero-label root name
+  pub fn is_empty(&self) -> bool {
+    self.label_count() == 0
+  }
+
+  /// iterates the labels in wire order (left to right); `.rev()` walks them
+  /// top-level-first, which is how `zone_of` matches suffixes.
+  pub fn iter(&self) -> LabelIter {
+    LabelIter { name: self, front: 0, back: self.label_count() }
+  }
+
   // for mutating over time
   pub fn with_labels(labels: Vec<String>) -> Self {
-    Name { labels: Rc::new(labels.into_iter().map(|s|Rc::new(s)).collect()) }
+    let mut name = Name::new();
+    for label in &labels { name.push_label(label); }
+    name
   }
 
   pub fn add_label(&mut self, label: Rc<String>) -> &mut Self {
-    // TODO get_mut() on Arc was unstable when this was written
-    let mut new_labels: Vec<Rc<String>> = (*self.labels).clone();
-    new_labels.push(label);
-    self.labels = Rc::new(new_labels);
+    self.push_label(&label);
     self
   }
 
   pub fn append(&mut self, other: &Self) -> &mut Self {
-    for rcs in &*other.labels {
-      self.add_label(rcs.clone());
+    for i in 0..other.label_count() {
+      self.push_label_bytes(other.label_bytes(i));
     }
 
     self
   }
 
+  /// appends a single label's bytes to the packed buffer. Mutates in place via
+  /// `Arc::make_mut` when this `Name` is uniquely owned, cloning the backing
+  /// buffers (once) only if it's shared -- the same copy-on-write the builder
+  /// methods used to hand-roll by cloning the whole label `Vec` on every call.
+  fn push_label(&mut self, label: &str) {
+    self.push_label_bytes(label.as_bytes());
+  }
+
+  /// appends a single label's raw wire octets to the packed buffer. Unlike
+  /// `push_label`, this makes no assumption that `label` is valid UTF-8 text --
+  /// it's the primitive `push_label` and `Name::parse`'s escape handling build on
+  /// top of, so an octet named by a `\DDD` escape lands on the wire unchanged.
+  ///
+  /// Panics if `label` doesn't fit in the `(u16, u8)` span encoding (i.e. is
+  /// longer than 255 octets): `parse()` already rejects an over-long label with
+  /// a `ParseError` before it would reach here, but the `label`/`add_label`/
+  /// `with_labels` builders take arbitrary text with no such check, and
+  /// silently truncating the stored length instead (via `as u8`) would desync
+  /// it from what's actually in `buf`, corrupting every label read after it.
+  fn push_label_bytes(&mut self, label: &[u8]) {
+    assert!(label.len() <= ::std::u8::MAX as usize,
+      "label is {} octets, which doesn't fit in this Name's 255-octet-max label encoding",
+      label.len());
+
+    let data = Rc::make_mut(&mut self.data);
+    let offset = data.buf.len() as u16;
+    data.buf.extend_from_slice(label);
+    data.spans.push((offset, label.len() as u8));
+  }
+
+  fn label_count(&self) -> usize {
+    self.data.spans.len()
+  }
+
+  fn label_bytes(&self, index: usize) -> &[u8] {
+    let (offset, len) = self.data.spans[index];
+    &self.data.buf[offset as usize .. offset as usize + len as usize]
+  }
+
   /// Trims off the first part of the name, to help with searching for the domain piece
   pub fn base_name(&self) -> Option<Name> {
-    if self.labels.len() >= 1 {
-      Some(Name { labels: Rc::new(self.labels[1..].to_vec()) } )
-    } else {
-      None
+    if self.is_empty() { return None; }
+
+    let mut trimmed = Name::new();
+    for label in self.iter().skip(1) {
+      trimmed.push_label_bytes(label);
     }
+    Some(trimmed)
   }
 
   /// returns true if the name components of self are all present at the end of name
   pub fn zone_of(&self, name: &Self) -> bool {
-    let self_len = self.labels.len();
-    let name_len = name.labels.len();
-
-    // TODO: there's probably a better way using iterators directly, but it wasn't obvious
-    for i in 1..(self_len+1) {
-      if self.labels.get(self_len - i) != name.labels.get(name_len - i) {
-        return false;
-      }
-    }
+    if self.len() > name.len() { return false; }
 
-    return true;
+    self.iter().rev().zip(name.iter().rev()).all(|(a, b)| a == b)
   }
 
-  // TODO: I think this does the wrong thing for escaped data
+  /// Parses a name out of a zone-file token, honoring RFC 1035 master-file escaping:
+  /// `\.` is a literal dot inside a label (not a label separator), `\\` is a literal
+  /// backslash, and `\DDD` (exactly three decimal digits) encodes an arbitrary octet
+  /// 0-255. A trailing, unescaped `.` marks `local` as fully-qualified, so `origin`
+  /// is not appended; otherwise `origin` is required and appended to the result.
   pub fn parse(local: &str, origin: Option<&Self>) -> ParseResult<Self> {
     let mut build = Name::new();
-    // split the local part
+    let mut label = String::new();
+    // the true wire octets of `label`, built up alongside it; used verbatim (not
+    // re-derived from `label`'s UTF-8 bytes) whenever the label turns out not to
+    // need IDNA, so an escaped octet >= 128 lands on the wire as the single octet
+    // it names rather than the multiple UTF-8 bytes `label.push(value as char)`
+    // would produce for it.
+    let mut raw = Vec::new();
+    // true once `label` has seen a genuinely-typed (not escaped) non-ASCII char,
+    // which is what should route it through IDNA; an escaped `\DDD` octet >= 128
+    // must never set this, or it gets corrupted into an xn-- label instead of
+    // being carried through like any other octet.
+    let mut has_extended = false;
+    let mut wire_len = 1usize; // accounts for the terminating root octet
+    let mut fqdn = false;
 
-    // TODO: this should be a real lexer, to varify all data is legal name...
-    for s in local.split('.') {
-      if s.len() > 0 {
-        build.add_label(Rc::new(s.to_string().to_lowercase())); // all names stored in lowercase
+    let mut chars = local.chars().peekable();
+    while let Some(ch) = chars.next() {
+      match ch {
+        '.' => {
+          if chars.peek().is_none() { fqdn = true; }
+          try!(Self::push_parsed_label(&mut build, &mut label, &mut raw, &mut has_extended, &mut wire_len));
+        },
+        '\\' => {
+          match try!(chars.next().ok_or(ParseError::UnexpectedEndOfInput)) {
+            '.' => { label.push('.'); raw.push(b'.'); },
+            '\\' => { label.push('\\'); raw.push(b'\\'); },
+            d @ '0'...'9' => {
+              let d2 = try!(chars.next().ok_or(ParseError::UnexpectedEndOfInput));
+              let d3 = try!(chars.next().ok_or(ParseError::UnexpectedEndOfInput));
+
+              let value = try!(Self::decimal_triple(d, d2, d3));
+              raw.push(value);
+              label.push(value as char);
+            },
+            c => return Err(ParseError::UnrecognizedEscape(c)),
+          }
+        },
+        ch => {
+          if !ch.is_ascii() { has_extended = true; } else { raw.push(ch as u8); }
+          label.push(ch);
+        },
       }
     }
 
-    if !local.ends_with('.') {
-      build.append(try!(origin.ok_or(ParseError::OriginIsUndefined)));
+    // whatever wasn't terminated by a trailing dot is the last label
+    try!(Self::push_parsed_label(&mut build, &mut label, &mut raw, &mut has_extended, &mut wire_len));
+
+    if !fqdn {
+      let origin = try!(origin.ok_or(ParseError::OriginIsUndefined));
+      for i in 0..origin.label_count() {
+        wire_len += 1 + origin.label_bytes(i).len();
+        if wire_len > 255 { return Err(ParseError::DomainNameTooLong(wire_len)); }
+      }
+      build.append(origin);
     }
 
     Ok(build)
   }
+
+  /// folds, IDNA-encodes and validates `label`, pushing the wire octets onto
+  /// `build` if non-empty, then clears `label`/`raw` for the next label.
+  /// Consecutive/leading/trailing dots simply yield no label, matching the
+  /// historical leniency of `Name::parse`.
+  ///
+  /// `has_extended` -- not `label.is_ascii()` -- decides whether IDNA applies: an
+  /// escaped `\DDD` octet (0-255) is a literal wire octet regardless of its value,
+  /// and must take the same non-IDNA path as plain ASCII text, or `\200` would
+  /// silently come out as an `xn--` label instead of the octet it names. That
+  /// path folds and counts `raw` (the actual octets) rather than `label` (a
+  /// `String`), since an escaped octet >= 128 is one octet but two UTF-8 bytes.
+  fn push_parsed_label(build: &mut Self, label: &mut String, raw: &mut Vec<u8>, has_extended: &mut bool, wire_len: &mut usize) -> ParseResult<()> {
+    if label.is_empty() { return Ok(()); }
+
+    let wire_label: Vec<u8> = if !*has_extended {
+      raw.iter().map(|&b| Self::fold_case(b)).collect()
+    } else {
+      // only ASCII is case-insensitive on the wire; fold what we can before
+      // punycoding so e.g. "EXAMPLE" and "example" still encode identically.
+      let folded: String = label.chars().map(|c| if c.is_ascii() { Self::fold_case(c as u8) as char } else { c }).collect();
+      format!("xn--{}", try!(punycode::encode(&folded))).into_bytes()
+    };
+
+    if wire_label.len() > 63 { return Err(ParseError::LabelBytesTooLong(wire_label.len())); }
+
+    *wire_len += 1 + wire_label.len();
+    if *wire_len > 255 { return Err(ParseError::DomainNameTooLong(*wire_len)); }
+
+    build.push_label_bytes(&wire_label);
+    label.clear();
+    raw.clear();
+    *has_extended = false;
+
+    Ok(())
+  }
+
+  /// parses three ASCII decimal digits into the octet they encode, per `\DDD` escapes
+  fn decimal_triple(d1: char, d2: char, d3: char) -> ParseResult<u8> {
+    let d2 = try!(d2.to_digit(10).ok_or(ParseError::UnrecognizedEscape(d2)));
+    let d3 = try!(d3.to_digit(10).ok_or(ParseError::UnrecognizedEscape(d3)));
+    let d1 = d1.to_digit(10).unwrap(); // caller already matched '0'...'9'
+
+    let value = d1 * 100 + d2 * 10 + d3;
+    if value > 255 { return Err(ParseError::EscapedOctetOutOfRange(value)); }
+
+    Ok(value as u8)
+  }
+
+  /// Compares `self` with `other` using the canonical DNS name ordering defined in
+  /// RFC 4034, section 6.1: labels are compared starting at the root end (rightmost)
+  /// and moving left, each label as a case-insensitive (ASCII-only) unsigned octet
+  /// string, and a name that is a proper suffix of another sorts first.
+  ///
+  /// The derived `Ord` on `Name` compares the packed label octets left-to-right
+  /// (then the label boundaries) and is case-sensitive;
+  /// it must not be used for NSEC ordering, RRSIG label counts, or anything else that
+  /// depends on the canonical form.
+  pub fn cmp_canonical(&self, other: &Self) -> Ordering {
+    let mut self_label_idx = self.label_count();
+    let mut other_label_idx = other.label_count();
+
+    loop {
+      match (self_label_idx.checked_sub(1), other_label_idx.checked_sub(1)) {
+        (Some(s), Some(o)) => {
+          self_label_idx = s;
+          other_label_idx = o;
+
+          let cmp = Self::cmp_label_canonical(self.label_bytes(self_label_idx), other.label_bytes(other_label_idx));
+          if cmp != Ordering::Equal { return cmp; }
+        },
+        (None, None) => return Ordering::Equal,
+        // self ran out of labels first, i.e. self is a proper suffix of other
+        (None, Some(_)) => return Ordering::Less,
+        (Some(_), None) => return Ordering::Greater,
+      }
+    }
+  }
+
+  /// compares two labels as case-insensitive (ASCII-only) unsigned octet strings
+  fn cmp_label_canonical(a: &[u8], b: &[u8]) -> Ordering {
+    let mut a = a.iter().cloned().map(Self::fold_case);
+    let mut b = b.iter().cloned().map(Self::fold_case);
+
+    loop {
+      match (a.next(), b.next()) {
+        (Some(x), Some(y)) => {
+          let cmp = x.cmp(&y);
+          if cmp != Ordering::Equal { return cmp; }
+        },
+        (None, None) => return Ordering::Equal,
+        (None, Some(_)) => return Ordering::Less,
+        (Some(_), None) => return Ordering::Greater,
+      }
+    }
+  }
+
+  /// folds only ASCII A-Z down to a-z, leaving every other octet untouched
+  fn fold_case(b: u8) -> u8 {
+    if b >= b'A' && b <= b'Z' { b + (b'a' - b'A') } else { b }
+  }
+
+  /// Emits the canonical wire form used for DNSSEC-signed RDATA: RFC 4034, section
+  /// 6.2 requires every label lowercased (ASCII-only) and forbids name compression,
+  /// so unlike `emit` this never consults or updates the encoder's label pointer table.
+  pub fn emit_canonical(&self, encoder: &mut BinEncoder) -> EncodeResult {
+    let buf_len = encoder.len();
+
+    for i in 0..self.label_count() {
+      let label = self.label_bytes(i);
+      if label.len() > 63 { return Err(EncodeError::LabelBytesTooLong(label.len())); }
+
+      // fold and emit over the raw octets directly -- going through `char`
+      // would re-encode any octet >= 128 as multiple UTF-8 bytes, corrupting
+      // the canonical RDATA for any such label.
+      let lower: Vec<u8> = label.iter().map(|&b| Self::fold_case(b)).collect();
+      try!(encoder.emit_character_data(&lower));
+    }
+
+    try!(encoder.emit(0));
+
+    let length = encoder.len() - buf_len;
+    if length > 255 { return Err(EncodeError::DomainNameTooLong(length)); }
+
+    Ok(())
+  }
 }
 
-impl BinSerializable for Name {
-  /// parses the chain of labels
-  ///  this has a max of 255 octets, with each label being less than 63.
-  ///  all names will be stored lowercase internally.
-  /// This will consume the portions of the Vec which it is reading...
-  fn read(decoder: &mut BinDecoder) -> DecodeResult<Name> {
+impl Name {
+  /// Each pointer must target a strictly earlier offset than the one at which it was
+  /// read, so the number of jumps is bounded by the message size; this cap just keeps
+  /// a single name from walking through an unreasonable number of labels/pointers.
+  /// Enforced against both the number of labels actually pushed and, separately, the
+  /// number of pointers actually followed -- a chain of pointers that never resolves
+  /// an intervening label (pointer -> pointer -> pointer ...) doesn't grow
+  /// `label_count()` at all, so it needs its own counter to stay bounded by this cap
+  /// rather than only by the strictly-decreasing-offset rule, which still permits
+  /// thousands of hops in a large message.
+  const MAX_LABELS: usize = 128;
+
+  /// as `read`, but `max_offset` bounds where a compression pointer encountered
+  /// while parsing this name is allowed to point. Every pointer followed must
+  /// target an offset strictly less than `max_offset`, and following it tightens
+  /// the bound to that offset for anything nested inside of it. This guarantees
+  /// termination: a pointer can never point at or after itself, so each jump
+  /// strictly reduces the remaining search space.
+  ///
+  /// Following a pointer reassigns `redirected` instead of recursing, so a chain
+  /// of pointers (bounded by the strictly-decreasing `max_offset` and, more
+  /// tightly, by `MAX_LABELS` pointers followed) advances this one loop rather
+  /// than growing the call stack.
+  fn read_with_bound(decoder: &mut BinDecoder, max_offset: u16) -> DecodeResult<Name> {
     let mut state: LabelParseState = LabelParseState::LabelLengthOrPointer;
-    let mut labels: Vec<Rc<String>> = Vec::with_capacity(3); // most labels will be around three, e.g. www.example.com
+    let mut name = Name::new();
+    let mut max_offset = max_offset;
+    let mut redirected: Option<BinDecoder> = None;
+    let mut pointers_followed = 0usize;
 
     // assume all chars are utf-8. We're doing byte-by-byte operations, no endianess issues...
     // reserved: (1000 0000 aka 0800) && (0100 0000 aka 0400)
@@ -123,72 +394,119 @@ impl BinSerializable for Name {
     // label: 03FF & slice = length; slice.next(length) = label
     // root: 0000
     loop {
-      state = match state {
-        LabelParseState::LabelLengthOrPointer => {
-          // determine what the next label is
-          match decoder.peek() {
-            Some(0) | None => LabelParseState::Root,
-            Some(byte) if byte & 0xC0 == 0xC0 => LabelParseState::Pointer,
-            Some(byte) if byte <= 0x3F        => LabelParseState::Label,
-            _ => unreachable!(),
-          }
-        },
-        LabelParseState::Label => {
-          labels.push(Rc::new(try!(decoder.read_character_data())));
+      if name.label_count() > Self::MAX_LABELS { return Err(DecodeError::TooManyPointers(Self::MAX_LABELS)); }
+      if pointers_followed > Self::MAX_LABELS { return Err(DecodeError::TooManyPointers(Self::MAX_LABELS)); }
 
-          // reset to collect more data
-          LabelParseState::LabelLengthOrPointer
-        },
-        //         4.1.4. Message compression
-        //
-        // In order to reduce the size of messages, the domain system utilizes a
-        // compression scheme which eliminates the repetition of domain names in a
-        // message.  In this scheme, an entire domain name or a list of labels at
-        // the end of a domain name is replaced with a pointer to a prior occurance
-        // of the same name.
-        //
-        // The pointer takes the form of a two octet sequence:
-        //
-        //     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
-        //     | 1  1|                OFFSET                   |
-        //     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
-        //
-        // The first two bits are ones.  This allows a pointer to be distinguished
-        // from a label, since the label must begin with two zero bits because
-        // labels are restricted to 63 octets or less.  (The 10 and 01 combinations
-        // are reserved for future use.)  The OFFSET field specifies an offset from
-        // the start of the message (i.e., the first octet of the ID field in the
-        // domain header).  A zero offset specifies the first byte of the ID field,
-        // etc.
-        LabelParseState::Pointer => {
-          let location = try!(decoder.read_u16()) & 0x3FFF; // get rid of the two high order bits
-          let mut pointer = decoder.clone(location);
-          let pointed = try!(Name::read(&mut pointer));
-
-          for l in &*pointed.labels {
-            labels.push(l.clone());
-          }
+      let mut new_redirect: Option<BinDecoder> = None;
 
-          // Pointers always finish the name, break like Root.
-          break;
-        },
-        LabelParseState::Root => {
-          // need to pop() the 0 off the stack...
-          try!(decoder.pop());
-          break;
+      state = {
+        let active: &mut BinDecoder = match redirected {
+          Some(ref mut d) => d,
+          None => &mut *decoder,
+        };
+
+        match state {
+          LabelParseState::LabelLengthOrPointer => {
+            // determine what the next label is
+            match active.peek() {
+              Some(0) | None => LabelParseState::Root,
+              Some(byte) if byte & 0xC0 == 0xC0 => LabelParseState::Pointer,
+              Some(byte) if byte <= 0x3F        => LabelParseState::Label,
+              _ => unreachable!(),
+            }
+          },
+          LabelParseState::Label => {
+            // wire labels are raw octets, not necessarily valid UTF-8 text --
+            // an internationalized label, or one carrying an octet a zone file
+            // would only be able to name via a `\DDD` escape, is entirely
+            // legal on the wire -- so this reads the undecoded bytes and
+            // pushes them verbatim via `push_label_bytes`, the same primitive
+            // `parse()`'s escape handling already goes through, rather than
+            // asserting UTF-8 validity on them.
+            let label = try!(active.read_character_data_bytes());
+            name.push_label_bytes(&label);
+
+            // reset to collect more data
+            LabelParseState::LabelLengthOrPointer
+          },
+          //         4.1.4. Message compression
+          //
+          // In order to reduce the size of messages, the domain system utilizes a
+          // compression scheme which eliminates the repetition of domain names in a
+          // message.  In this scheme, an entire domain name or a list of labels at
+          // the end of a domain name is replaced with a pointer to a prior occurance
+          // of the same name.
+          //
+          // The pointer takes the form of a two octet sequence:
+          //
+          //     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+          //     | 1  1|                OFFSET                   |
+          //     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+          //
+          // The first two bits are ones.  This allows a pointer to be distinguished
+          // from a label, since the label must begin with two zero bits because
+          // labels are restricted to 63 octets or less.  (The 10 and 01 combinations
+          // are reserved for future use.)  The OFFSET field specifies an offset from
+          // the start of the message (i.e., the first octet of the ID field in the
+          // domain header).  A zero offset specifies the first byte of the ID field,
+          // etc.
+          //
+          // A pointer that targets the current position or anything after it (or
+          // after the nearest pointer already followed) would recurse/loop forever,
+          // so every pointer must strictly decrease `max_offset` for whatever it
+          // points to.
+          LabelParseState::Pointer => {
+            let location = try!(active.read_u16()) & 0x3FFF; // get rid of the two high order bits
+            if location >= max_offset { return Err(DecodeError::PointerNotDecreasing(location)); }
+            max_offset = location;
+            pointers_followed += 1;
+
+            new_redirect = Some(active.clone(location));
+
+            // labels continue to be collected from the pointer's target
+            LabelParseState::LabelLengthOrPointer
+          },
+          LabelParseState::Root => {
+            // need to pop() the 0 off the stack...
+            try!(active.pop());
+            return Ok(name);
+          }
         }
-      }
+      };
+
+      if new_redirect.is_some() { redirected = new_redirect; }
     }
+  }
+}
 
-    Ok(Name { labels: Rc::new(labels) })
+impl BinSerializable for Name {
+  /// parses the chain of labels
+  ///  this has a max of 255 octets, with each label being less than 63.
+  ///  label octets are stored exactly as read, with no case folding.
+  /// This will consume the portions of the Vec which it is reading...
+  fn read(decoder: &mut BinDecoder) -> DecodeResult<Name> {
+    // a pointer encountered while parsing this name may only point at an earlier
+    // offset than where this name itself started.
+    let start = decoder.index() as u16;
+    Name::read_with_bound(decoder, start)
   }
 
   fn emit(&self, encoder: &mut BinEncoder) -> EncodeResult {
 
     let buf_len = encoder.len(); // lazily assert the size is less than 255...
+
+    // the encoder's label-pointer cache is keyed on `&[Rc<Vec<u8>>]`; materializing
+    // that here (rather than storing labels that way ourselves) keeps the packed
+    // buffer representation a purely internal detail of `Name`. Raw octets, not a
+    // `String`, so a label carrying a `\DDD`-escaped octet >= 128 (not valid UTF-8
+    // on its own) emits unchanged instead of panicking on re-encode.
+    let rc_labels: Vec<Rc<Vec<u8>>> = (0..self.label_count())
+      .map(|i| Rc::new(self.label_bytes(i).to_vec()))
+      .collect();
+
     // lookup the label in the BinEncoder
     // if it exists, write the Pointer
-    let mut labels: &[Rc<String>] = &self.labels;
+    let mut labels: &[Rc<Vec<u8>>] = &rc_labels;
     while let Some(label) = labels.first() {
       // before we write the label, let's look for the current set of labels.
       if let Some(loc) = encoder.get_label_pointer(labels) {
@@ -224,23 +542,278 @@ impl BinSerializable for Name {
   }
 }
 
+impl Name {
+  /// Renders the name using the raw ASCII-compatible-encoded (ACE) wire labels,
+  /// i.e. internationalized labels are shown in their `xn--` form. Equivalent to
+  /// `Display`.
+  pub fn to_ascii(&self) -> String {
+    format!("{}", self)
+  }
+
+  /// Renders the name with every `xn--` (punycode) label decoded back to Unicode
+  /// for display; labels that aren't ACE-encoded are rendered as `Display`
+  /// renders them (valid UTF-8 unchanged, other octets as `\DDD` escapes).
+  /// Honors root/FQDN the same way `Display` does: the root renders as `.`, and
+  /// a relative name (see `is_fqdn`) omits the trailing dot.
+  pub fn to_utf8(&self) -> String {
+    if self.is_root() { return ".".to_string(); }
+
+    let mut s = String::new();
+    for (i, label) in self.iter().enumerate() {
+      if i > 0 { s.push('.'); }
+
+      // only valid-UTF-8, ASCII `xn--` labels are candidates for punycode
+      // decoding; anything else (including a label that merely looks like
+      // punycode but doesn't decode) falls through to the raw rendering.
+      let decoded = ::std::str::from_utf8(label).ok()
+        .and_then(|l| if l.starts_with("xn--") { punycode::decode(&l[4..]).ok() } else { None });
+
+      match decoded {
+        Some(unicode) => s.push_str(&unicode),
+        None => Self::render_label(label, &mut s).expect("String writes never fail"),
+      }
+    }
+
+    if self.is_fqdn() { s.push('.'); }
+
+    s
+  }
+
+  /// renders a label's raw wire octets as human-readable text: valid UTF-8 is
+  /// written through unchanged (this is the common case, and the only way a
+  /// label built from real Unicode text -- as opposed to a `\DDD` escape --
+  /// renders correctly); a label that isn't valid UTF-8 as a whole is rendered
+  /// octet-by-octet, with each non-printable-ASCII octet (including one named by
+  /// a `\DDD` escape that doesn't happen to form valid UTF-8, e.g. a lone 0xC8)
+  /// shown as its own `\DDD` escape rather than panicking on it.
+  fn render_label<W: fmt::Write>(label: &[u8], out: &mut W) -> fmt::Result {
+    if let Ok(s) = ::std::str::from_utf8(label) {
+      return out.write_str(s);
+    }
+
+    for &b in label {
+      if b >= 0x20 && b < 0x7F {
+        try!(out.write_char(b as char));
+      } else {
+        try!(write!(out, "\\{:03}", b));
+      }
+    }
+
+    Ok(())
+  }
+}
+
 impl fmt::Display for Name {
+  /// writes the raw wire labels, i.e. internationalized labels in their ACE
+  /// (`xn--`) form; see `to_utf8` for a human-readable Unicode rendering. A
+  /// label whose octets aren't valid UTF-8 (e.g. one carrying a `\DDD`-escaped
+  /// octet that doesn't happen to form valid UTF-8) renders octet-by-octet via
+  /// `\DDD` escapes rather than panicking; see `render_label`.
+  /// The root name renders as `.`; a relative name (see `is_fqdn`) omits the
+  /// trailing dot that would otherwise mark it as fully-qualified.
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    for label in &*self.labels {
-      write!(f, "{}.", *label);
+    if self.is_root() { return write!(f, "."); }
+
+    for (i, label) in self.iter().enumerate() {
+      if i > 0 { try!(write!(f, ".")); }
+      try!(Self::render_label(label, f));
     }
+
+    if self.is_fqdn() { try!(write!(f, ".")); }
+
     Ok(())
   }
 }
 
+/// A minimal implementation of the Punycode bootstring encoding (RFC 3492), used
+/// to convert individual internationalized labels to/from their ASCII-compatible
+/// (`xn--`) wire form. Only the portion of the label after the `xn--` prefix is
+/// handled here; the prefix itself is added/stripped by the caller.
+mod punycode {
+  use ::error::*;
+
+  const BASE: u32 = 36;
+  const TMIN: u32 = 1;
+  const TMAX: u32 = 26;
+  const SKEW: u32 = 38;
+  const DAMP: u32 = 700;
+  const INITIAL_BIAS: u32 = 72;
+  const INITIAL_N: u32 = 128;
+
+  fn adapt(mut delta: u32, num_points: u32, first_time: bool) -> u32 {
+    delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+      delta /= BASE - TMIN;
+      k += BASE;
+    }
+
+    k + (((BASE - TMIN + 1) * delta) / (delta + SKEW))
+  }
+
+  fn digit_to_char(d: u32) -> char {
+    // 0-25 -> a-z, 26-35 -> 0-9
+    if d < 26 { (b'a' + d as u8) as char } else { (b'0' + (d - 26) as u8) as char }
+  }
+
+  fn char_to_digit(c: char) -> ParseResult<u32> {
+    match c {
+      'a'...'z' => Ok(c as u32 - 'a' as u32),
+      'A'...'Z' => Ok(c as u32 - 'A' as u32),
+      '0'...'9' => Ok(c as u32 - '0' as u32 + 26),
+      _ => Err(ParseError::UnrecognizedEscape(c)),
+    }
+  }
+
+  /// encodes a label's extended (non-ASCII) text into the string that follows the
+  /// `xn--` prefix on the wire. Labels are capped at 63 octets on the wire, so the
+  /// running totals below stay well within `u32` and need no overflow checks.
+  pub fn encode(input: &str) -> ParseResult<String> {
+    let chars: Vec<char> = input.chars().collect();
+    // the encoded form is at least one octet per input char, so anything longer
+    // could never fit in a 63-octet label; reject it before the arithmetic below
+    if chars.len() > 63 { return Err(ParseError::LabelBytesTooLong(chars.len())); }
+    let basic: Vec<char> = chars.iter().cloned().filter(|c| c.is_ascii()).collect();
+
+    let mut output = String::new();
+    for c in &basic { output.push(*c); }
+    let mut handled = basic.len() as u32;
+    if handled > 0 { output.push('-'); }
+
+    let total = chars.len() as u32;
+    let mut n = INITIAL_N;
+    let mut delta = 0u32;
+    let mut bias = INITIAL_BIAS;
+
+    while handled < total {
+      let m = try!(chars.iter().cloned().map(|c| c as u32).filter(|&cp| cp >= n).min()
+        .ok_or(ParseError::UnrecognizedEscape('\0')));
+
+      delta += (m - n) * (handled + 1);
+      n = m;
+
+      for &c in &chars {
+        let cp = c as u32;
+        if cp < n { delta += 1; }
+        if cp == n {
+          let mut q = delta;
+          let mut k = BASE;
+          loop {
+            let t = if k <= bias { TMIN } else if k >= bias + TMAX { TMAX } else { k - bias };
+            if q < t { break; }
+            output.push(digit_to_char(t + ((q - t) % (BASE - t))));
+            q = (q - t) / (BASE - t);
+            k += BASE;
+          }
+          output.push(digit_to_char(q));
+          bias = adapt(delta, handled + 1, handled == basic.len() as u32);
+          delta = 0;
+          handled += 1;
+        }
+      }
+
+      delta += 1;
+      n += 1;
+    }
+
+    Ok(output)
+  }
+
+  /// decodes the string that follows an `xn--` prefix back to the original Unicode text
+  pub fn decode(input: &str) -> ParseResult<String> {
+    let (basic, extended) = match input.rfind('-') {
+      Some(pos) => (&input[..pos], &input[pos + 1..]),
+      None => ("", input),
+    };
+
+    let mut output: Vec<char> = basic.chars().collect();
+    let extended: Vec<char> = extended.chars().collect();
+    let mut pos = 0usize;
+
+    let mut n = INITIAL_N;
+    let mut i = 0u32;
+    let mut bias = INITIAL_BIAS;
+
+    while pos < extended.len() {
+      let old_i = i;
+      let mut w = 1u32;
+      let mut k = BASE;
+
+      loop {
+        if pos >= extended.len() { return Err(ParseError::UnexpectedEndOfInput); }
+        let digit = try!(char_to_digit(extended[pos]));
+        pos += 1;
+
+        // checked, since a crafted xn-- label can carry enough digits to
+        // overflow `u32`; that's reported as a decode error like any other
+        i = try!(digit.checked_mul(w).and_then(|dw| i.checked_add(dw))
+          .ok_or(ParseError::EscapedOctetOutOfRange(n)));
+        let t = if k <= bias { TMIN } else if k >= bias + TMAX { TMAX } else { k - bias };
+        if digit < t { break; }
+
+        w = try!(w.checked_mul(BASE - t).ok_or(ParseError::EscapedOctetOutOfRange(n)));
+        k += BASE;
+      }
+
+      let len = output.len() as u32 + 1;
+      bias = adapt(i - old_i, len, old_i == 0);
+      n = try!(n.checked_add(i / len).ok_or(ParseError::EscapedOctetOutOfRange(n)));
+      i = i % len;
+
+      let ch = try!(::std::char::from_u32(n).ok_or(ParseError::EscapedOctetOutOfRange(n)));
+      output.insert(i as usize, ch);
+      i += 1;
+    }
+
+    Ok(output.into_iter().collect())
+  }
+}
+
 impl Index<usize> for Name {
-    type Output = String;
+    // a label's raw wire octets aren't necessarily valid UTF-8 -- a `\DDD` escape
+    // names an arbitrary octet -- so this indexes into the packed buffer itself
+    // rather than asserting a `str` out of it; see `Display`/`to_utf8` for a
+    // human-readable (`\DDD`-escaping) rendering.
+    type Output = [u8];
 
-    fn index<'a>(&'a self, _index: usize) -> &'a String {
-        &*(self.labels[_index])
+    fn index<'a>(&'a self, _index: usize) -> &'a [u8] {
+        self.label_bytes(_index)
     }
 }
 
+/// Iterates a `Name`'s labels in wire order (left to right); reversible via the
+/// standard `DoubleEndedIterator::rev`, which walks top-level-label-first. Yields
+/// raw wire octets, not `&str`, since a label built from a `\DDD` escape is not
+/// necessarily valid UTF-8; see `Display`/`to_utf8` for human-readable rendering.
+pub struct LabelIter<'a> {
+  name: &'a Name,
+  front: usize,
+  back: usize,
+}
+
+impl<'a> Iterator for LabelIter<'a> {
+  type Item = &'a [u8];
+
+  fn next(&mut self) -> Option<&'a [u8]> {
+    if self.front >= self.back { return None; }
+
+    let label = self.name.label_bytes(self.front);
+    self.front += 1;
+    Some(label)
+  }
+}
+
+impl<'a> DoubleEndedIterator for LabelIter<'a> {
+  fn next_back(&mut self) -> Option<&'a [u8]> {
+    if self.front >= self.back { return None; }
+
+    self.back -= 1;
+    Some(self.name.label_bytes(self.back))
+  }
+}
+
 /// This is the list of states for the label parsing state machine
 enum LabelParseState {
   LabelLengthOrPointer, // basically the start of the FSM
@@ -283,7 +856,6 @@ mod tests {
     let third = Name::new().label("rc");
     let fourth = Name::new().label("z").label("ra").label("rb").label("rc");
 
-
     first.emit(&mut e).unwrap();
     assert_eq!(e.len(), 10); // should be 7 u8s...
 
@@ -297,7 +869,6 @@ mod tests {
     fourth.emit(&mut e).unwrap();
     assert_eq!(e.len(), 18);
 
-
     // now read them back
     let bytes = e.as_bytes();
     let mut d = BinDecoder::new(&bytes);
@@ -325,4 +896,246 @@ mod tests {
     assert!(zone.zone_of(&www));
     assert!(!zone.zone_of(&none))
   }
+
+  #[test]
+  fn test_iter_based_methods_and_display_handle_non_utf8_labels() {
+    // a label carrying a `\DDD`-escaped octet (here 0xC8) is not necessarily
+    // valid UTF-8; every `LabelIter`-based method, plus `Display` (which
+    // renders non-UTF-8 octets back out as `\DDD`), must handle it rather than
+    // panicking on the assumption that a label is a `str`.
+    let name = Name::parse("a\\200b.com.", None).unwrap();
+
+    assert_eq!(format!("{}", name), "a\\200b.com.");
+
+    let mut appended = Name::new().label("www");
+    appended.append(&name);
+    assert_eq!(format!("{}", appended), "www.a\\200b.com.");
+
+    assert!(name.zone_of(&name.clone()));
+    assert_eq!(name.base_name(), Some(Name::new().label("com")));
+    assert_eq!(name.cmp_canonical(&name.clone()), Ordering::Equal);
+  }
+
+  #[test]
+  fn test_indexing_and_base_name_over_packed_storage() {
+    let name = Name::new().label("www").label("example").label("com");
+
+    assert_eq!(&name[0], &b"www"[..]);
+    assert_eq!(&name[1], &b"example"[..]);
+    assert_eq!(&name[2], &b"com"[..]);
+
+    assert_eq!(name.base_name(), Some(Name::new().label("example").label("com")));
+
+    // sharing the same Rc-backed data and then mutating one clone must not affect the other
+    let mut shared = name.clone();
+    shared.add_label(Rc::new("org".to_string()));
+    assert_eq!(name, Name::new().label("www").label("example").label("com"));
+    assert_eq!(shared, Name::new().label("www").label("example").label("com").label("org"));
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_add_label_over_255_octets_panics() {
+    // the (u16, u8) span encoding can't represent a label longer than 255
+    // octets; silently truncating the stored length (via `as u8`) would desync
+    // it from what's actually in `buf`, corrupting every label stored after
+    // it, so this must panic instead.
+    let too_long = Rc::new(::std::iter::repeat('a').take(256).collect::<String>());
+    Name::new().add_label(too_long);
+  }
+
+  #[test]
+  fn test_iter_and_rev() {
+    let name = Name::new().label("www").label("example").label("com");
+
+    let forward: Vec<&[u8]> = name.iter().collect();
+    assert_eq!(forward, vec![&b"www"[..], &b"example"[..], &b"com"[..]]);
+
+    let backward: Vec<&[u8]> = name.iter().rev().collect();
+    assert_eq!(backward, vec![&b"com"[..], &b"example"[..], &b"www"[..]]);
+
+    assert_eq!(name.len(), 3);
+    assert!(!name.is_empty());
+  }
+
+  #[test]
+  fn test_is_root_and_fqdn_display() {
+    let root = Name::new();
+    assert!(root.is_root());
+    assert!(root.is_fqdn());
+    assert_eq!(format!("{}", root), ".");
+
+    let absolute = Name::new().label("example").label("com");
+    assert!(absolute.is_fqdn());
+    assert_eq!(format!("{}", absolute), "example.com.");
+
+    let relative = Name::new().label("example").label("com").fqdn(false);
+    assert!(!relative.is_fqdn());
+    assert_eq!(format!("{}", relative), "example.com");
+  }
+
+  #[test]
+  fn test_parse_idna_label_round_trips() {
+    // bücher -> xn--bcher-kva, per RFC 3492's own punycode sample set
+    let name = Name::parse("b\u{00fc}cher.com.", None).unwrap();
+    assert_eq!(name, Name::new().label("xn--bcher-kva").label("com"));
+    assert_eq!(name.to_utf8(), "b\u{00fc}cher.com.");
+  }
+
+  #[test]
+  fn test_to_utf8_honors_root_and_fqdn_like_display() {
+    let root = Name::new();
+    assert_eq!(root.to_utf8(), ".");
+
+    let relative = Name::new().label("xn--bcher-kva").label("com").fqdn(false);
+    assert_eq!(relative.to_utf8(), "b\u{00fc}cher.com");
+  }
+
+  #[test]
+  fn test_to_utf8_renders_non_utf8_label_as_escaped_octet() {
+    // `to_utf8` is the headline API for safely rendering a parsed name; a label
+    // carrying a `\DDD`-escaped octet that isn't valid UTF-8 on its own (here
+    // 0xC8) must come back out escaped the same way, not panic.
+    let name = Name::parse("a\\200b.com.", None).unwrap();
+    assert_eq!(name.to_utf8(), "a\\200b.com.");
+  }
+
+  #[test]
+  fn test_parse_escaped_dot_is_not_a_separator() {
+    let name = Name::parse("a\\.b.com.", None).unwrap();
+    assert_eq!(name, Name::new().label("a.b").label("com"));
+  }
+
+  #[test]
+  fn test_parse_escaped_backslash_and_octet() {
+    // \099 is the decimal escape for octet 99, i.e. ASCII 'c'
+    let name = Name::parse("a\\\\b.\\099om.", None).unwrap();
+    assert_eq!(name, Name::new().label("a\\b").label("com"));
+  }
+
+  #[test]
+  fn test_parse_fqdn_vs_relative() {
+    let origin = Name::new().label("example").label("com");
+
+    let fqdn = Name::parse("www.", Some(&origin)).unwrap();
+    assert_eq!(fqdn, Name::new().label("www"));
+
+    let relative = Name::parse("www", Some(&origin)).unwrap();
+    assert_eq!(relative, Name::new().label("www").label("example").label("com"));
+  }
+
+  #[test]
+  fn test_parse_escaped_high_octet_is_not_idna_encoded() {
+    // \200 is the decimal escape for octet 200 (0xC8); it's a literal octet, not
+    // real Unicode text, so it must take the plain ASCII path, not punycode.
+    let name = Name::parse("a\\200b.com.", None).unwrap();
+    assert!(!name[0].starts_with(b"xn--"));
+
+    // and it must round-trip on the wire as the single octet 0xC8, not as the
+    // two UTF-8 bytes (0xC3 0x88) that `value as char` would have produced.
+    let mut e = BinEncoder::new();
+    name.emit(&mut e).unwrap();
+    let bytes = e.as_bytes();
+    assert_eq!(&bytes[..6], &[3, b'a', 0xC8, b'b', 3, b'c']);
+
+    let mut d = BinDecoder::new(&bytes);
+    assert_eq!(Name::read(&mut d).unwrap(), name);
+  }
+
+  #[test]
+  fn test_indexing_non_utf8_label_does_not_panic() {
+    // the label carrying octet 0xC8 is not valid UTF-8 on its own; `Index` must
+    // yield its raw wire octets rather than asserting a `str` out of them.
+    let name = Name::parse("a\\200b.com.", None).unwrap();
+    assert_eq!(&name[0], &[b'a', 0xC8, b'b'][..]);
+  }
+
+  #[test]
+  fn test_parse_rejects_bad_escape() {
+    assert!(Name::parse("a\\z.com.", None).is_err());
+    assert!(Name::parse("a\\999.com.", None).is_err());
+  }
+
+  #[test]
+  fn test_cmp_canonical() {
+    // RFC 4034, section 6.1 example ordering (top-level label compared first)
+    let a = Name::new().label("example");
+    let b = Name::new().label("a").label("example");
+    let c = Name::new().label("yljkjljk").label("a").label("example");
+    let d = Name::new().label("Z").label("a").label("example");
+    let e = Name::new().label("zABC").label("a").label("example");
+
+    assert_eq!(a.cmp_canonical(&a), Ordering::Equal);
+    assert_eq!(a.cmp_canonical(&b), Ordering::Less);
+    assert_eq!(b.cmp_canonical(&c), Ordering::Less);
+    assert_eq!(c.cmp_canonical(&d), Ordering::Less);
+    assert_eq!(d.cmp_canonical(&e), Ordering::Less);
+
+    // case is ignored
+    let upper = Name::new().label("WWW").label("EXAMPLE").label("COM");
+    let lower = Name::new().label("www").label("example").label("com");
+    assert_eq!(upper.cmp_canonical(&lower), Ordering::Equal);
+  }
+
+  #[test]
+  fn test_emit_canonical_lowercases_and_never_compresses() {
+    let mut e = BinEncoder::new();
+
+    let upper = Name::new().label("WWW").label("Example").label("COM");
+    upper.emit_canonical(&mut e).unwrap();
+
+    // a second, identical-looking name must not be replaced by a pointer
+    let again = Name::new().label("www").label("example").label("com");
+    let first_len = e.len();
+    again.emit_canonical(&mut e).unwrap();
+
+    // both writes are the full uncompressed form: 4+8+4+1 bytes each
+    assert_eq!(e.len() - first_len, first_len);
+
+    let bytes = e.as_bytes();
+    assert_eq!(&bytes[..first_len], &bytes[first_len..]);
+    assert_eq!(bytes[1], b'w'); // lowercased on the wire
+  }
+
+  #[test]
+  fn test_pointer_loop_rejected() {
+    // a pointer that targets itself: offset 0, so `location == max_offset` and must be rejected.
+    let bytes: Vec<u8> = vec![0xC0, 0x00];
+    let mut d = BinDecoder::new(&bytes);
+    assert!(Name::read(&mut d).is_err());
+  }
+
+  #[test]
+  fn test_pointer_must_decrease() {
+    // two pointers that point at each other would loop forever if pointers were
+    // allowed to jump forward; the second pointer (at offset 2) points at the
+    // first (offset 0), which is fine, but the first, if it pointed at offset 2,
+    // must be rejected since 2 is not less than its own offset of 0.
+    let bytes: Vec<u8> = vec![0xC0, 0x02, 0xC0, 0x00];
+    let mut d = BinDecoder::new(&bytes);
+    assert!(Name::read(&mut d).is_err());
+  }
+
+  #[test]
+  fn test_long_pointer_chain_without_labels_is_bounded() {
+    // a descending chain of pointers that never resolves an actual label in
+    // between (pointer -> pointer -> pointer ... -> root) never grows
+    // `label_count()`, so the cap on labels pushed alone wouldn't stop it --
+    // it needs its own counter on pointers actually followed, even though
+    // each hop here is individually legal (strictly decreasing offsets).
+    let hops = Name::MAX_LABELS + 2;
+
+    let mut bytes: Vec<u8> = vec![0]; // offset 0: the root
+    for i in 0..hops {
+      let target = if i == 0 { 0u16 } else { 1 + (i as u16 - 1) * 2 };
+      bytes.push(0xC0 | ((target >> 8) as u8));
+      bytes.push((target & 0xFF) as u8);
+    }
+    let start = 1 + (hops as u16 - 1) * 2;
+
+    let mut d = BinDecoder::new(&bytes);
+    for _ in 0..start { d.pop().unwrap(); }
+
+    assert!(Name::read(&mut d).is_err());
+  }
 }
\ No newline at end of file